@@ -0,0 +1,248 @@
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use rustls::server::{ClientCertVerified, ClientCertVerifier};
+use rustls::{Certificate, DistinguishedNames, Error as TlsError};
+
+/// Details pulled from a client's TLS certificate after a handshake.
+#[derive(Clone, Debug)]
+pub struct ClientCert {
+    pub subject: String,
+    pub not_before: x509_parser::time::ASN1Time,
+    pub not_after: x509_parser::time::ASN1Time,
+    /// Hex-encoded SHA-256 fingerprint of the DER-encoded certificate.
+    pub fingerprint: String,
+}
+
+impl ClientCert {
+    /// Whether `not_before <= now <= not_after` for this certificate.
+    #[must_use]
+    pub fn is_time_valid(&self) -> bool {
+        let now = x509_parser::time::ASN1Time::now();
+        now >= self.not_before && now <= self.not_after
+    }
+}
+
+/// Parses the leaf certificate presented by a client, if any.
+pub fn extract_client_cert(certs: &[Certificate]) -> Option<ClientCert> {
+    let leaf = certs.first()?;
+    let (_, parsed) = x509_parser::parse_x509_certificate(&leaf.0).ok()?;
+
+    let fingerprint = {
+        use sha2::{Digest, Sha256};
+        let digest = Sha256::digest(&leaf.0);
+        digest.iter().map(|b| format!("{:02x}", b)).collect::<String>()
+    };
+
+    Some(ClientCert {
+        subject: parsed.subject().to_string(),
+        not_before: parsed.validity().not_before,
+        not_after: parsed.validity().not_after,
+        fingerprint,
+    })
+}
+
+/// Accepts any client certificate; `process_request` applies the actual
+/// trust policy per path.
+#[derive(Debug)]
+pub struct AnyClientCertVerifier;
+
+impl AnyClientCertVerifier {
+    #[must_use]
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self)
+    }
+}
+
+impl ClientCertVerifier for AnyClientCertVerifier {
+    fn offer_client_auth(&self) -> bool {
+        true
+    }
+
+    fn client_auth_mandatory(&self) -> Option<bool> {
+        // Optional: paths that don't require a cert must still work.
+        Some(false)
+    }
+
+    fn client_auth_root_subjects(&self) -> Option<DistinguishedNames> {
+        Some(DistinguishedNames::new())
+    }
+
+    fn verify_client_cert(
+        &self,
+        _end_entity: &Certificate,
+        _intermediates: &[Certificate],
+        _now: SystemTime,
+    ) -> Result<ClientCertVerified, TlsError> {
+        Ok(ClientCertVerified::assertion())
+    }
+}
+
+/// A single `path-prefix: fingerprint fingerprint ...` rule from `.certauth`.
+/// An empty `allowed` list (written as `*`) accepts any time-valid cert.
+#[derive(Clone, Debug)]
+struct CertRule {
+    prefix: String,
+    allowed: Vec<String>,
+}
+
+/// Per-path client-certificate requirements, loaded from `.certauth`.
+#[derive(Clone, Debug, Default)]
+pub struct CertPolicy {
+    rules: Vec<CertRule>,
+}
+
+/// Outcome of checking a request path against a [`CertPolicy`].
+pub enum CertDecision {
+    /// No rule matched the path.
+    NotRequired,
+    /// A rule matched and the certificate satisfies it.
+    Allowed,
+    /// A rule matched but no certificate was presented.
+    Required,
+    /// A rule matched but the certificate is expired/not yet valid.
+    Invalid,
+    /// A rule matched but the certificate isn't in the allow list.
+    Unauthorized,
+}
+
+impl CertPolicy {
+    /// Loads `prefix: fp1 fp2 ...` rules from a `.certauth` file. A missing
+    /// file yields an empty (permissive) policy.
+    pub fn load(path: &str) -> Self {
+        let contents = match std::fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(_) => return Self::default(),
+        };
+
+        let rules = contents
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .filter_map(|line| line.split_once(':'))
+            .map(|(prefix, allowed)| CertRule {
+                prefix: prefix.trim().to_string(),
+                allowed: allowed
+                    .split_whitespace()
+                    .filter(|fp| *fp != "*")
+                    .map(str::to_string)
+                    .collect(),
+            })
+            .collect();
+
+        Self { rules }
+    }
+
+    fn matching_rule(&self, path: &str) -> Option<&CertRule> {
+        self.rules
+            .iter()
+            .filter(|rule| path.starts_with(rule.prefix.as_str()))
+            .max_by_key(|rule| rule.prefix.len())
+    }
+
+    /// Decides whether `path` may be served given `cert`.
+    #[must_use]
+    pub fn check(&self, path: &str, cert: Option<&ClientCert>) -> CertDecision {
+        let Some(rule) = self.matching_rule(path) else {
+            return CertDecision::NotRequired;
+        };
+
+        let Some(cert) = cert else {
+            return CertDecision::Required;
+        };
+
+        if !cert.is_time_valid() {
+            return CertDecision::Invalid;
+        }
+
+        if rule.allowed.is_empty() || rule.allowed.contains(&cert.fingerprint) {
+            CertDecision::Allowed
+        } else {
+            CertDecision::Unauthorized
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(contents: &str) -> CertPolicy {
+        let rules = contents
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .filter_map(|line| line.split_once(':'))
+            .map(|(prefix, allowed)| CertRule {
+                prefix: prefix.trim().to_string(),
+                allowed: allowed
+                    .split_whitespace()
+                    .filter(|fp| *fp != "*")
+                    .map(str::to_string)
+                    .collect(),
+            })
+            .collect();
+        CertPolicy { rules }
+    }
+
+    fn cert(fingerprint: &str, time_valid: bool) -> ClientCert {
+        // 2020-01-01 and 2100-01-01, well outside any real certificate's
+        // validity window in either direction.
+        let past = x509_parser::time::ASN1Time::from_timestamp(1_577_836_800).unwrap();
+        let future = x509_parser::time::ASN1Time::from_timestamp(4_102_444_800).unwrap();
+        ClientCert {
+            subject: "CN=test".to_string(),
+            not_before: if time_valid { past } else { future },
+            not_after: if time_valid { future } else { past },
+            fingerprint: fingerprint.to_string(),
+        }
+    }
+
+    #[test]
+    fn no_matching_rule_is_not_required() {
+        let p = policy("/private: abc123\n");
+        assert!(matches!(p.check("/public/page.gmi", None), CertDecision::NotRequired));
+    }
+
+    #[test]
+    fn matching_rule_without_cert_is_required() {
+        let p = policy("/private: abc123\n");
+        assert!(matches!(p.check("/private/page.gmi", None), CertDecision::Required));
+    }
+
+    #[test]
+    fn wildcard_rule_allows_any_time_valid_cert() {
+        let p = policy("/private: *\n");
+        let cert = cert("abc123", true);
+        assert!(matches!(p.check("/private/page.gmi", Some(&cert)), CertDecision::Allowed));
+    }
+
+    #[test]
+    fn allow_listed_fingerprint_is_allowed() {
+        let p = policy("/private: abc123 def456\n");
+        let cert = cert("abc123", true);
+        assert!(matches!(p.check("/private/page.gmi", Some(&cert)), CertDecision::Allowed));
+    }
+
+    #[test]
+    fn unlisted_fingerprint_is_unauthorized() {
+        let p = policy("/private: abc123\n");
+        let cert = cert("other", true);
+        assert!(matches!(p.check("/private/page.gmi", Some(&cert)), CertDecision::Unauthorized));
+    }
+
+    #[test]
+    fn expired_cert_is_invalid() {
+        let p = policy("/private: *\n");
+        let cert = cert("abc123", false);
+        assert!(matches!(p.check("/private/page.gmi", Some(&cert)), CertDecision::Invalid));
+    }
+
+    #[test]
+    fn longest_matching_prefix_wins() {
+        let p = policy("/: *\n/private: abc123\n");
+        let cert = cert("other", true);
+        assert!(matches!(p.check("/private/page.gmi", Some(&cert)), CertDecision::Unauthorized));
+        assert!(matches!(p.check("/public/page.gmi", Some(&cert)), CertDecision::Allowed));
+    }
+}
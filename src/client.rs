@@ -0,0 +1,143 @@
+use std::sync::Arc;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio_rustls::TlsConnector;
+
+use crate::response::{Meta, Response};
+use crate::status::Code;
+
+/// Default Gemini port, used when a proxied URL doesn't specify one.
+const GEMINI_PORT: u16 = 1965;
+
+fn client_tls_config() -> Arc<rustls::ClientConfig> {
+    let mut roots = rustls::RootCertStore::empty();
+    roots.add_server_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.0.iter().map(|ta| {
+        rustls::OwnedTrustAnchor::from_subject_spki_name_constraints(
+            ta.subject,
+            ta.spki,
+            ta.name_constraints,
+        )
+    }));
+
+    let cfg = rustls::ClientConfig::builder()
+        .with_safe_defaults()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+
+    Arc::new(cfg)
+}
+
+fn proxy_error(message: impl Into<String>) -> Response {
+    let message = message.into();
+    Response::ProxyError(Meta::new(&message).unwrap_or_else(|_| {
+        Meta::new("failed to proxy request").expect("fallback meta fits")
+    }))
+}
+
+/// Validates an untrusted upstream server's raw META text, logging and
+/// substituting a fallback instead of forwarding a rejected value.
+fn sanitized_upstream_meta(meta: &str) -> Meta {
+    Meta::new(meta).unwrap_or_else(|e| {
+        log::warn!("rejecting upstream meta : {}", e);
+        Meta::new("upstream meta rejected").expect("fallback meta fits")
+    })
+}
+
+/// Proxies a Gemini request to `url`'s host, mapping the upstream response
+/// back into a local [`Response`].
+pub async fn request(url: &url::Url) -> Response {
+    if url.scheme() != "gemini" {
+        return proxy_error(format!("cannot proxy non-gemini scheme : {}", url.scheme()));
+    }
+
+    if !url.username().is_empty() || url.password().is_some() {
+        return proxy_error("userinfo is not allowed in proxied urls");
+    }
+
+    let host = match url.host_str() {
+        Some(host) => host,
+        None => return proxy_error("url has no host to proxy to"),
+    };
+    let port = url.port().unwrap_or(GEMINI_PORT);
+
+    match fetch(host, port, url.as_str()).await {
+        Ok(response) => response,
+        Err(e) => proxy_error(format!("failed to proxy request : {}", e)),
+    }
+}
+
+async fn fetch(
+    host: &str,
+    port: u16,
+    request_line: &str,
+) -> Result<Response, Box<dyn std::error::Error + Send + Sync>> {
+    let connector = TlsConnector::from(client_tls_config());
+    let stream = TcpStream::connect((host, port)).await?;
+    let server_name = rustls::ServerName::try_from(host)?;
+    let mut stream = connector.connect(server_name, stream).await?;
+
+    stream
+        .write_all(format!("{}\r\n", request_line).as_bytes())
+        .await?;
+
+    let mut upstream_response = Vec::new();
+    stream.read_to_end(&mut upstream_response).await?;
+
+    parse_response(&upstream_response)
+}
+
+/// Parses an upstream `<STATUS> <META>\r\n<BODY>` response into a [`Response`].
+fn parse_response(bytes: &[u8]) -> Result<Response, Box<dyn std::error::Error + Send + Sync>> {
+    let header_end = bytes
+        .windows(2)
+        .position(|window| window == b"\r\n")
+        .ok_or("upstream response is missing a header line")?;
+    let header = std::str::from_utf8(&bytes[..header_end])?;
+    let body = bytes[header_end + 2..].to_vec();
+
+    let (code, header_meta) = header.split_once(' ').unwrap_or((header, ""));
+    let code: u8 = code.parse()?;
+    let status = Code::from(code);
+    let upstream_meta = sanitized_upstream_meta(header_meta);
+
+    Ok(if status.is_success() {
+        Response::Success(upstream_meta, body)
+    } else if status.is_input() {
+        match status {
+            Code::SensitiveInput => Response::SensitiveInput(upstream_meta),
+            _ => Response::Input(upstream_meta),
+        }
+    } else if status.is_redirect() {
+        match status {
+            Code::RedirectTemporary => Response::RedirectTemporary(upstream_meta),
+            _ => Response::RedirectPermanent(upstream_meta),
+        }
+    } else if status.is_temporary_failure() {
+        match status {
+            Code::ServerUnavailable => Response::ServerUnavailable(upstream_meta),
+            Code::CgiError => Response::CgiError(upstream_meta),
+            Code::ProxyError => Response::ProxyError(upstream_meta),
+            Code::SlowDown => Response::SlowDown(upstream_meta),
+            _ => Response::TemporaryFailure(upstream_meta),
+        }
+    } else if status.is_permanent_failure() {
+        match status {
+            Code::NotFound => Response::NotFound(upstream_meta),
+            Code::Gone => Response::Gone(upstream_meta),
+            Code::ProxyRequestRefused => Response::ProxyRequestRefused(upstream_meta),
+            Code::BadRequest => Response::BadRequest(upstream_meta),
+            _ => Response::PermanentFailure(upstream_meta),
+        }
+    } else if status.is_client_certification_failure() {
+        match status {
+            Code::ClientCertificationUnauthorized => {
+                Response::ClientCertificationUnauthorized(upstream_meta)
+            }
+            Code::ClientCertificateNotValid => Response::ClientCertificateNotValid(upstream_meta),
+            _ => Response::ClientCertificationRequired(upstream_meta),
+        }
+    } else {
+        return Err(format!("invalid upstream status code : {}", code).into());
+    })
+}
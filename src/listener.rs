@@ -0,0 +1,116 @@
+use std::fmt;
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::{TcpListener, TcpStream, UnixListener, UnixStream};
+
+/// Either a TCP or a Unix-domain-socket listener, picked in [`bind`] by what
+/// `--addr` looks like.
+pub enum Listener {
+    Tcp(TcpListener),
+    Unix(UnixListener),
+}
+
+/// Binds `addr` as a Unix-domain-socket if it looks like a filesystem path
+/// (contains a `/`), otherwise as a TCP `host:port` listener.
+pub async fn bind(addr: &str) -> io::Result<Listener> {
+    if addr.contains('/') {
+        // Remove a stale socket file left behind by an unclean shutdown.
+        let _ = std::fs::remove_file(addr);
+        Ok(Listener::Unix(UnixListener::bind(addr)?))
+    } else {
+        Ok(Listener::Tcp(TcpListener::bind(addr).await?))
+    }
+}
+
+impl Listener {
+    pub async fn accept(&self) -> io::Result<(Connection, PeerAddr)> {
+        match self {
+            Self::Tcp(listener) => {
+                let (socket, addr) = listener.accept().await?;
+                Ok((Connection::Tcp(socket), PeerAddr::Tcp(addr)))
+            }
+            Self::Unix(listener) => {
+                let (socket, addr) = listener.accept().await?;
+                Ok((Connection::Unix(socket), PeerAddr::Unix(addr)))
+            }
+        }
+    }
+
+    /// Name of the transport in use, for the startup log line.
+    pub fn transport_name(&self) -> &'static str {
+        match self {
+            Self::Tcp(_) => "tcp",
+            Self::Unix(_) => "unix",
+        }
+    }
+}
+
+/// An accepted connection from either transport.
+pub enum Connection {
+    Tcp(TcpStream),
+    Unix(UnixStream),
+}
+
+impl AsyncRead for Connection {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Self::Tcp(stream) => Pin::new(stream).poll_read(cx, buf),
+            Self::Unix(stream) => Pin::new(stream).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for Connection {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            Self::Tcp(stream) => Pin::new(stream).poll_write(cx, buf),
+            Self::Unix(stream) => Pin::new(stream).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Self::Tcp(stream) => Pin::new(stream).poll_flush(cx),
+            Self::Unix(stream) => Pin::new(stream).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Self::Tcp(stream) => Pin::new(stream).poll_shutdown(cx),
+            Self::Unix(stream) => Pin::new(stream).poll_shutdown(cx),
+        }
+    }
+}
+
+/// Where a connection came from, for logging.
+pub enum PeerAddr {
+    Tcp(std::net::SocketAddr),
+    Unix(tokio::net::unix::SocketAddr),
+}
+
+impl fmt::Display for PeerAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Tcp(addr) => write!(f, "{}", addr),
+            Self::Unix(addr) => write!(
+                f,
+                "{}",
+                addr.as_pathname()
+                    .map(|path| path.display().to_string())
+                    .unwrap_or_else(|| "<unnamed unix socket>".to_string())
+            ),
+        }
+    }
+}
@@ -1,11 +1,17 @@
 use clap::Parser;
 use percent_encoding::{AsciiSet, CONTROLS};
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::net::TcpListener;
+use tokio::io::AsyncReadExt;
 use tokio_rustls::TlsAcceptor;
 
+pub mod cert;
+pub mod client;
+pub mod listener;
+pub mod meta;
 pub mod response;
-use response::Response;
+pub mod status;
+use cert::{CertDecision, CertPolicy, ClientCert};
+use meta::MetaCache;
+use response::{Meta, Response};
 
 /// URL percent encoding/decoding ascii set
 const URL_PERCENT_ENCODING: &AsciiSet = &CONTROLS
@@ -46,37 +52,133 @@ struct Args {
     /// Static file directory
     #[clap(long, short = 'd')]
     data: String,
+
+    /// Generate a self-signed certificate/key pair at `--cert`/`--key` if they
+    /// don't already exist, instead of requiring them to be provided.
+    #[clap(long)]
+    gen_cert: bool,
+
+    /// Regenerate the self-signed certificate/key pair at `--cert`/`--key`
+    /// even if they already exist. Overwrites existing material, so it also
+    /// changes the TOFU fingerprint clients have on file for this capsule.
+    #[clap(long)]
+    force_gen_cert: bool,
+
+    /// Hostname this capsule is served as. Used as the common name / DNS SAN
+    /// for `--gen-cert` and to tell local requests apart from proxy targets.
+    /// Defaults to the host portion of `--addr`, except when `--addr` is a
+    /// Unix-domain-socket path, where it has no such host portion and
+    /// `--hostname` must be given explicitly.
+    #[clap(long)]
+    hostname: Option<String>,
+
+    /// Proxy requests for other hosts to their own Gemini server instead of
+    /// refusing them with `Response::ProxyRequestRefused`.
+    #[clap(long)]
+    allow_proxy: bool,
+
+    /// Only negotiate TLS 1.3, rejecting clients that only offer TLS 1.2.
+    #[clap(long)]
+    tls13_only: bool,
 }
 
+/// Derives the hostname a request must target to be served locally, from
+/// `--hostname` if given or else the host portion of `--addr`.
+///
+/// `--addr` has no host portion when it's a Unix-domain-socket path, so
+/// `--hostname` is required in that case; otherwise every request's URL host
+/// would silently fail to match and get refused or proxied.
+///
+/// Lowercased, since `gemini` isn't a WHATWG "special" scheme and `url::Url`
+/// doesn't normalize its host case the way it does for `http`/`https`.
+fn local_hostname(args: &Args) -> String {
+    if let Some(hostname) = &args.hostname {
+        return hostname.to_ascii_lowercase();
+    }
+
+    if args.addr.contains('/') {
+        panic!("--hostname must be given explicitly when --addr is a Unix-domain-socket path");
+    }
+
+    args.addr
+        .rsplit_once(':')
+        .map_or_else(|| args.addr.clone(), |(host, _)| host.to_string())
+        .to_ascii_lowercase()
+}
+
+/// Name of the per-capsule file listing path prefixes that require a client certificate.
+const CERT_AUTH_FILE: &str = ".certauth";
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     let args = Args::parse();
-    let listener = TcpListener::bind(args.addr)
+    let server_listener = listener::bind(&args.addr)
         .await
-        .expect("failed to start tcp listener");
+        .expect("failed to start listener");
 
     env_logger::init();
 
+    let local_host = local_hostname(&args);
+
+    let certs_missing = !std::path::Path::new(&args.cert).exists()
+        && !std::path::Path::new(&args.key).exists();
+    if args.force_gen_cert || (args.gen_cert && certs_missing) {
+        let hostname = &local_host;
+        log::info!(
+            "Generating self-signed certificate for {} at {} / {}",
+            hostname,
+            args.cert,
+            args.key
+        );
+        generate_self_signed_cert(hostname, &args.cert, &args.key)
+            .expect("failed to generate self-signed certificate");
+    }
+
     // Build TLS configuration.
     let tls_cfg = {
         // Load public certificate.
         let certs = load_certs(&args.cert);
         // Load private key.
         let key = load_private_key(&args.key);
-        // Do not use client certificate authentication.
+
+        let versions: &[&rustls::SupportedProtocolVersion] = if args.tls13_only {
+            &[&rustls::version::TLS13]
+        } else {
+            rustls::ALL_VERSIONS
+        };
+
+        // Accept any client certificate; per-path policy decides what to do with it.
         let cfg = rustls::ServerConfig::builder()
-            .with_safe_defaults()
-            .with_no_client_auth()
+            .with_safe_default_cipher_suites()
+            .with_safe_default_kx_groups()
+            .with_protocol_versions(versions)
+            .expect("failed to select tls protocol versions")
+            .with_client_cert_verifier(cert::AnyClientCertVerifier::new())
             .with_single_cert(certs, key)
             .expect("failed to generate tls config");
         std::sync::Arc::new(cfg)
     };
 
+    log::info!(
+        "Listening on {} via {} ({})",
+        args.addr,
+        server_listener.transport_name(),
+        if args.tls13_only { "TLS 1.3 only" } else { "default TLS versions" }
+    );
+
     std::env::set_current_dir(args.data.clone()).expect("failed to set work dir");
 
+    let cert_policy = std::sync::Arc::new(CertPolicy::load(CERT_AUTH_FILE));
+    let meta_cache = std::sync::Arc::new(MetaCache::new());
+    let local_host = std::sync::Arc::new(local_host);
+    let allow_proxy = args.allow_proxy;
+
     loop {
-        let (socket, addr) = listener.accept().await?;
+        let (socket, addr) = server_listener.accept().await?;
         let tls_cfg = tls_cfg.clone();
+        let cert_policy = cert_policy.clone();
+        let meta_cache = meta_cache.clone();
+        let local_host = local_host.clone();
 
         tokio::spawn(async move {
             let mut acceptor = match TlsAcceptor::from(tls_cfg).accept(socket).await {
@@ -87,6 +189,12 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
                 }
             };
 
+            let client_cert = acceptor
+                .get_ref()
+                .1
+                .peer_certificates()
+                .and_then(cert::extract_client_cert);
+
             let mut url_buffer = [0; 2048];
             let url_result = acceptor.read(&mut url_buffer).await;
             let closure = || async move {
@@ -94,23 +202,26 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
                 match url_result {
                     Ok(bytes_read) => {
                         byte_count = bytes_read;
-                        if byte_count > 1024 {
-                            return Response::BadRequest("url exceeds 1024 bytes".into());
+                        if byte_count > response::MAX_HEADER_LEN {
+                            return Response::BadRequest(meta(format!(
+                                "url exceeds {} bytes",
+                                response::MAX_HEADER_LEN
+                            )));
                         }
                     }
-                    Err(e) => return Response::BadRequest(format!("Failed to get url : {} ", e)),
+                    Err(e) => return Response::BadRequest(meta(format!("Failed to get url : {} ", e))),
                 };
 
                 let url_string = match std::str::from_utf8(&url_buffer[..byte_count]) {
                     Ok(url) => url,
                     Err(e) => {
-                        return Response::BadRequest(format!("url is not valid UTF-8 : {}", e))
+                        return Response::BadRequest(meta(format!("url is not valid UTF-8 : {}", e)))
                     }
                 };
 
                 let url = match url::Url::parse(url_string) {
                     Ok(url) => url,
-                    Err(e) => return Response::BadRequest(format!("url is valid : {}", e)),
+                    Err(e) => return Response::BadRequest(meta(format!("url is not valid : {}", e))),
                 };
 
                 log::info!(
@@ -118,16 +229,45 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
                     addr,
                     url_string.strip_suffix("\r\n").unwrap_or(url_string).trim()
                 );
-                process_request(url).await
+                process_request(
+                    url,
+                    &cert_policy,
+                    client_cert.as_ref(),
+                    &meta_cache,
+                    &local_host,
+                    allow_proxy,
+                )
+                .await
             };
 
-            if let Err(e) = acceptor.write(&closure().await.as_bytes()).await {
+            if let Err(e) = closure().await.write_to(&mut acceptor).await {
                 log::error!("Failed to send response to client : {}", e);
             }
         });
     }
 }
 
+// Generate a self-signed certificate/key pair for `hostname` and write them
+// as PEM to `cert_path`/`key_path`.
+fn generate_self_signed_cert(
+    hostname: &str,
+    cert_path: &str,
+    key_path: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut params = rcgen::CertificateParams::new(vec![hostname.to_string()]);
+    params.distinguished_name = rcgen::DistinguishedName::new();
+    params
+        .distinguished_name
+        .push(rcgen::DnType::CommonName, hostname);
+
+    let cert = rcgen::Certificate::from_params(params)?;
+
+    std::fs::write(cert_path, cert.serialize_pem()?)?;
+    std::fs::write(key_path, cert.serialize_private_key_pem())?;
+
+    Ok(())
+}
+
 // Load public certificates from file.
 fn load_certs(filename: &str) -> Vec<rustls::Certificate> {
     // Open certificate file.
@@ -145,17 +285,57 @@ fn load_private_key(filename: &str) -> rustls::PrivateKey {
     let keyfile = std::fs::File::open(filename).unwrap();
     let mut reader = std::io::BufReader::new(keyfile);
 
-    // Load and return a single private key.
-    let keys = rustls_pemfile::rsa_private_keys(&mut reader).unwrap();
-    if keys.len() != 1 {
-        panic!("expected a single private key");
-    }
+    // Load and return a single private key, trying PKCS8 first since that is
+    // what `--gen-cert` produces, then falling back to RSA.
+    let keys = rustls_pemfile::pkcs8_private_keys(&mut reader).unwrap();
+    let key = if keys.len() == 1 {
+        keys[0].clone()
+    } else {
+        let keyfile = std::fs::File::open(filename).unwrap();
+        let mut reader = std::io::BufReader::new(keyfile);
+        let keys = rustls_pemfile::rsa_private_keys(&mut reader).unwrap();
+        if keys.len() != 1 {
+            panic!("expected a single private key");
+        }
+        keys[0].clone()
+    };
 
-    rustls::PrivateKey(keys[0].clone())
+    rustls::PrivateKey(key)
+}
+
+/// Builds a [`Meta`] for a response header, falling back to a notice
+/// matching the failure (and logging why) instead of emitting a
+/// non-conformant header.
+fn meta(value: impl Into<String>) -> Meta {
+    Meta::new(value).unwrap_or_else(|e| {
+        log::warn!("{}", e);
+        let fallback = match e {
+            response::MetaError::TooLong(_) => "response meta exceeds the protocol limit",
+            response::MetaError::ControlChar(_) => "response meta contains an invalid character",
+        };
+        Meta::new(fallback).expect("fallback meta fits")
+    })
 }
 
 /// Server response code
-async fn process_request(url: url::Url) -> Response {
+async fn process_request(
+    url: url::Url,
+    cert_policy: &CertPolicy,
+    client_cert: Option<&ClientCert>,
+    meta_cache: &MetaCache,
+    local_host: &str,
+    allow_proxy: bool,
+) -> Response {
+    if let Some(host) = url.host_str() {
+        if host.to_ascii_lowercase() != local_host {
+            return if allow_proxy {
+                client::request(&url).await
+            } else {
+                Response::ProxyRequestRefused(meta("proxying is disabled on this server"))
+            };
+        }
+    }
+
     let path = match percent_encoding::percent_decode_str(url.path())
         .decode_utf8_lossy()
         .to_string()
@@ -165,10 +345,30 @@ async fn process_request(url: url::Url) -> Response {
         path => path,
     };
 
+    // `.certauth` prefixes are URL paths (`/private`), so check against the
+    // request path with its leading slash rather than the stripped file path.
+    let request_path = if path == "." { "/".to_string() } else { format!("/{}", path) };
+    match cert_policy.check(&request_path, client_cert) {
+        CertDecision::NotRequired | CertDecision::Allowed => {}
+        CertDecision::Required => {
+            return Response::ClientCertificationRequired(meta("client certificate required"))
+        }
+        CertDecision::Invalid => {
+            return Response::ClientCertificateNotValid(meta(
+                "client certificate expired or not yet valid",
+            ))
+        }
+        CertDecision::Unauthorized => {
+            return Response::ClientCertificationUnauthorized(meta(
+                "client certificate not authorized for this path",
+            ))
+        }
+    }
+
     if path == "robots.txt" {
         return match std::fs::read(".robots.txt") {
-            Ok(bytes) => Response::Success("text/plain".into(), bytes),
-            Err(_) => Response::Success("text/plain".into(), "".into()),
+            Ok(bytes) => Response::Success(meta("text/plain"), bytes),
+            Err(_) => Response::Success(meta("text/plain"), "".into()),
         };
     }
 
@@ -211,21 +411,14 @@ async fn process_request(url: url::Url) -> Response {
                 )
                 .into_bytes();
 
-                Response::Success("text/gemini".into(), body)
+                Response::Success(meta("text/gemini"), body)
             }
-            Err(e) => Response::CgiError(format!("Failed to generate directory list : {}", e)),
+            Err(e) => Response::CgiError(meta(format!("Failed to generate directory list : {}", e))),
         },
-        Ok(file) if file.is_file() => match std::fs::read(path.clone()) {
-            Ok(bytes) => {
-                let default_mime: mime::Mime = "text/gemini".parse().unwrap();
-                let mime = mime_guess::from_path(path.clone())
-                    .first()
-                    .unwrap_or(default_mime.clone());
-
-                Response::Success(format!("{}", mime), bytes)
-            }
-            Err(e) => Response::CgiError(format!("Failed to read file : {}", e)),
+        Ok(file) if file.is_file() => match tokio::fs::File::open(path.clone()).await {
+            Ok(file) => Response::SuccessStream(meta(meta_cache.resolve(&path)), file),
+            Err(e) => Response::CgiError(meta(format!("Failed to read file : {}", e))),
         },
-        _ => Response::NotFound("Not found".into()),
+        _ => Response::NotFound(meta("Not found")),
     }
 }
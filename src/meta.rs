@@ -0,0 +1,96 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+/// Name of the sidecar file in each served directory that overrides the META
+/// field for files matching a glob.
+const META_FILE: &str = ".meta";
+
+/// A single `glob: META` rule from a `.meta` file.
+#[derive(Clone, Debug)]
+struct MetaRule {
+    glob: glob::Pattern,
+    meta: String,
+}
+
+/// Parsed rule set for one directory, in file order (first match wins).
+#[derive(Clone, Debug, Default)]
+struct MetaRules(Vec<MetaRule>);
+
+impl MetaRules {
+    fn load(dir: &str) -> Self {
+        let path = if dir.is_empty() || dir == "." {
+            META_FILE.to_string()
+        } else {
+            format!("{}/{}", dir, META_FILE)
+        };
+
+        let contents = match std::fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(_) => return Self::default(),
+        };
+
+        let rules = contents
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .filter_map(|line| line.split_once(':'))
+            .filter_map(|(glob, meta)| {
+                glob::Pattern::new(glob.trim())
+                    .ok()
+                    .map(|glob| MetaRule {
+                        glob,
+                        meta: meta.trim().to_string(),
+                    })
+            })
+            .collect();
+
+        Self(rules)
+    }
+
+    fn meta_for(&self, file_name: &str) -> Option<&str> {
+        self.0
+            .iter()
+            .find(|rule| rule.glob.matches(file_name))
+            .map(|rule| rule.meta.as_str())
+    }
+}
+
+/// Caches parsed `.meta` rule sets per directory.
+#[derive(Default)]
+pub struct MetaCache {
+    rules: Mutex<HashMap<String, MetaRules>>,
+}
+
+impl MetaCache {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Resolves the META field for `path`, falling back to `mime_guess` (and
+    /// then `text/gemini`) if no `.meta` rule matches.
+    pub fn resolve(&self, path: &str) -> String {
+        let (dir, file_name) = match path.rsplit_once('/') {
+            Some((dir, file_name)) => (dir, file_name),
+            None => ("", path),
+        };
+
+        if let Some(meta) = self.rules_for(dir).meta_for(file_name) {
+            return meta.to_string();
+        }
+
+        let default_mime: mime::Mime = "text/gemini".parse().unwrap();
+        mime_guess::from_path(path)
+            .first()
+            .unwrap_or(default_mime)
+            .to_string()
+    }
+
+    fn rules_for(&self, dir: &str) -> MetaRules {
+        let mut cache = self.rules.lock().unwrap();
+        cache
+            .entry(dir.to_string())
+            .or_insert_with(|| MetaRules::load(dir))
+            .clone()
+    }
+}
@@ -1,100 +1,230 @@
+/// Maximum length in bytes of a full Gemini response header line
+/// (`<STATUS><SPACE><META><CR><LF>`), per the Gemini spec.
+pub const MAX_HEADER_LEN: usize = 1024;
+
+/// Fixed overhead of a two-digit status code, the separating space, and the
+/// trailing CRLF; whatever's left of [`MAX_HEADER_LEN`] is available for META.
+const HEADER_OVERHEAD_LEN: usize = 2 + 1 + 2;
+
+/// Maximum length in bytes of the META field alone.
+pub const MAX_META_LEN: usize = MAX_HEADER_LEN - HEADER_OVERHEAD_LEN;
+
+/// Error returned when text can't be turned into a valid [`Meta`].
+#[derive(Clone, Debug)]
+pub enum MetaError {
+    /// The META field is longer than [`MAX_META_LEN`] bytes.
+    TooLong(usize),
+    /// The META field contains a control character (e.g. a bare `\r` or
+    /// `\n`) that would let it smuggle extra lines into the header.
+    ControlChar(char),
+}
+
+impl std::fmt::Display for MetaError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::TooLong(len) => write!(
+                f,
+                "meta field is {} bytes, exceeds the {} byte limit",
+                len, MAX_META_LEN
+            ),
+            Self::ControlChar(c) => {
+                write!(f, "meta field contains control character {:?}", c)
+            }
+        }
+    }
+}
+
+impl std::error::Error for MetaError {}
+
+/// A validated Gemini response META field.
+///
+/// Constructing a `Meta` is the only way to put text into a [`Response`]
+/// header, and it is where the 1024-byte header limit and the no-embedded-
+/// newline rule are enforced, so [`Response::as_bytes`] never has to fail or
+/// emit a non-conformant header.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Meta(String);
+
+impl Meta {
+    /// Validates `meta` against [`MAX_META_LEN`] and rejects any control
+    /// character (notably `\r`/`\n`, which would splice a second line into
+    /// the header) so a constructed `Meta` is always a single conformant line.
+    pub fn new(meta: impl Into<String>) -> Result<Self, MetaError> {
+        let meta = meta.into();
+        if let Some(c) = meta.chars().find(|c| c.is_control()) {
+            return Err(MetaError::ControlChar(c));
+        }
+        if meta.len() > MAX_META_LEN {
+            return Err(MetaError::TooLong(meta.len()));
+        }
+        Ok(Self(meta))
+    }
+}
+
+impl std::fmt::Display for Meta {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
 /// Gemini possible responses.
 ///
 /// Parameters are formated so first is the <META> field
 /// and the second is the <BODY> field.
-#[derive(Clone, Debug)]
+#[derive(Debug)]
 pub enum Response {
-    Input(String),
-    SensitiveInput(String),
+    Input(Meta),
+    SensitiveInput(Meta),
 
-    Success(String, Vec<u8>),
+    Success(Meta, Vec<u8>),
+    /// Same as [`Self::Success`], but the body is streamed from the open
+    /// file instead of being held in memory, for large files.
+    SuccessStream(Meta, tokio::fs::File),
 
-    RedirectPermanent(String),
-    RedirectTemporary(String),
+    RedirectPermanent(Meta),
+    RedirectTemporary(Meta),
 
-    TemporaryFailure(String),
-    ServerUnavailable(String),
-    CgiError(String),
-    ProxyError(String),
-    SlowDown(String),
+    TemporaryFailure(Meta),
+    ServerUnavailable(Meta),
+    CgiError(Meta),
+    ProxyError(Meta),
+    SlowDown(Meta),
 
-    PermanentFailure(String),
-    NotFound(String),
-    Gone(String),
-    ProxyRequestRefused(String),
+    PermanentFailure(Meta),
+    NotFound(Meta),
+    Gone(Meta),
+    ProxyRequestRefused(Meta),
 
-    BadRequest(String),
+    BadRequest(Meta),
 
-    ClientCertificationRequired(String),
-    ClientCertificationUnauthorized(String),
-    ClientCertificateNotValid(String),
+    ClientCertificationRequired(Meta),
+    ClientCertificationUnauthorized(Meta),
+    ClientCertificateNotValid(Meta),
 }
 
 impl Response {
+    /// The [`Code`](crate::status::Code) this response serializes as.
+    fn code(&self) -> crate::status::Code {
+        use crate::status::Code;
+        match self {
+            Self::Input(_) => Code::Input,
+            Self::SensitiveInput(_) => Code::SensitiveInput,
+
+            Self::Success(_, _) | Self::SuccessStream(_, _) => Code::Success,
+
+            Self::RedirectPermanent(_) => Code::RedirectPermanent,
+            Self::RedirectTemporary(_) => Code::RedirectTemporary,
+
+            Self::TemporaryFailure(_) => Code::TemporaryFailure,
+            Self::ServerUnavailable(_) => Code::ServerUnavailable,
+            Self::CgiError(_) => Code::CgiError,
+            Self::ProxyError(_) => Code::ProxyError,
+            Self::SlowDown(_) => Code::SlowDown,
+
+            Self::PermanentFailure(_) => Code::PermanentFailure,
+            Self::NotFound(_) => Code::NotFound,
+            Self::Gone(_) => Code::Gone,
+            Self::ProxyRequestRefused(_) => Code::ProxyRequestRefused,
+
+            Self::BadRequest(_) => Code::BadRequest,
+
+            Self::ClientCertificationRequired(_) => Code::ClientCertificationRequired,
+            Self::ClientCertificationUnauthorized(_) => Code::ClientCertificationUnauthorized,
+            Self::ClientCertificateNotValid(_) => Code::ClientCertificateNotValid,
+        }
+    }
+
     #[must_use]
     pub fn as_bytes(&self) -> Vec<u8> {
         let mut result = Vec::<u8>::new();
-        match self {
-            Self::Input(meta) => {
-                result.extend_from_slice(format!("10 {}\r\n", meta).as_bytes());
-            }
-            Self::SensitiveInput(meta) => {
-                result.extend_from_slice(format!("11 {}\r\n", meta).as_bytes());
-            }
+        let header = |meta: &Meta| format!("{:02} {}\r\n", self.code() as u8, meta).into_bytes();
 
+        match self {
             Self::Success(meta, body) => {
-                result.extend_from_slice(format!("20 {}\r\n", meta).as_bytes());
+                result.extend(header(meta));
                 result.extend(body);
             }
+            // The body is streamed separately by `write_to`; only the header
+            // is available without awaiting a read from the file.
+            Self::SuccessStream(meta, _) => result.extend(header(meta)),
 
-            Self::RedirectPermanent(meta) => {
-                result.extend_from_slice(format!("30 {}\r\n", meta).as_bytes());
-            }
-            Self::RedirectTemporary(meta) => {
-                result.extend_from_slice(format!("31 {}\r\n", meta).as_bytes());
-            }
+            Self::Input(meta)
+            | Self::SensitiveInput(meta)
+            | Self::RedirectPermanent(meta)
+            | Self::RedirectTemporary(meta)
+            | Self::TemporaryFailure(meta)
+            | Self::ServerUnavailable(meta)
+            | Self::CgiError(meta)
+            | Self::ProxyError(meta)
+            | Self::SlowDown(meta)
+            | Self::PermanentFailure(meta)
+            | Self::NotFound(meta)
+            | Self::Gone(meta)
+            | Self::ProxyRequestRefused(meta)
+            | Self::BadRequest(meta)
+            | Self::ClientCertificationRequired(meta)
+            | Self::ClientCertificationUnauthorized(meta)
+            | Self::ClientCertificateNotValid(meta) => result.extend(header(meta)),
+        }
+        result
+    }
 
-            Self::TemporaryFailure(meta) => {
-                result.extend_from_slice(format!("40 {}\r\n", meta).as_bytes());
-            }
-            Self::ServerUnavailable(meta) => {
-                result.extend_from_slice(format!("41 {}\r\n", meta).as_bytes());
-            }
-            Self::CgiError(meta) => {
-                result.extend_from_slice(format!("42 {}\r\n", meta).as_bytes());
-            }
-            Self::ProxyError(meta) => {
-                result.extend_from_slice(format!("43 {}\r\n", meta).as_bytes());
-            }
-            Self::SlowDown(meta) => {
-                result.extend_from_slice(format!("44 {}\r\n", meta).as_bytes());
-            }
+    /// Writes the full response to `writer`, streaming the body in
+    /// fixed-size chunks for [`Self::SuccessStream`] instead of buffering it,
+    /// so serving a large file doesn't hold it entirely in memory.
+    pub async fn write_to<W>(self, writer: &mut W) -> std::io::Result<()>
+    where
+        W: tokio::io::AsyncWrite + Unpin,
+    {
+        use tokio::io::AsyncWriteExt;
 
-            Self::PermanentFailure(meta) => {
-                result.extend_from_slice(format!("50 {}\r\n", meta).as_bytes());
-            }
-            Self::NotFound(meta) => {
-                result.extend_from_slice(format!("51 {}\r\n", meta).as_bytes());
-            }
-            Self::Gone(meta) => result.extend_from_slice(format!("51 {}\r\n", meta).as_bytes()),
-            Self::ProxyRequestRefused(meta) => {
-                result.extend_from_slice(format!("52 {}\r\n", meta).as_bytes());
-            }
+        match self {
+            Self::SuccessStream(meta, mut file) => {
+                writer
+                    .write_all(
+                        format!("{:02} {}\r\n", crate::status::Code::Success as u8, meta)
+                            .as_bytes(),
+                    )
+                    .await?;
+                tokio::io::copy(&mut file, writer).await?;
+                Ok(())
+            }
+            other => writer.write_all(&other.as_bytes()).await,
+        }
+    }
+}
 
-            Self::BadRequest(meta) => {
-                result.extend_from_slice(format!("59 {}\r\n", meta).as_bytes());
-            }
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-            Self::ClientCertificationRequired(meta) => {
-                result.extend_from_slice(format!("60 {}\r\n", meta).as_bytes());
-            }
-            Self::ClientCertificationUnauthorized(meta) => {
-                result.extend_from_slice(format!("61 {}\r\n", meta).as_bytes());
-            }
-            Self::ClientCertificateNotValid(meta) => {
-                result.extend_from_slice(format!("62 {}\r\n", meta).as_bytes());
-            }
-        }
-        result
+    #[test]
+    fn accepts_plain_text() {
+        assert!(Meta::new("text/gemini").is_ok());
+    }
+
+    #[test]
+    fn rejects_bare_lf() {
+        assert!(matches!(
+            Meta::new("text/gemini\nSmuggled: header"),
+            Err(MetaError::ControlChar('\n'))
+        ));
+    }
+
+    #[test]
+    fn rejects_bare_cr() {
+        assert!(matches!(Meta::new("text/gemini\r"), Err(MetaError::ControlChar('\r'))));
+    }
+
+    #[test]
+    fn rejects_over_length() {
+        let meta = "a".repeat(MAX_META_LEN + 1);
+        assert!(matches!(Meta::new(meta), Err(MetaError::TooLong(_))));
+    }
+
+    #[test]
+    fn accepts_exact_length() {
+        let meta = "a".repeat(MAX_META_LEN);
+        assert!(Meta::new(meta).is_ok());
     }
 }